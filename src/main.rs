@@ -3,9 +3,15 @@ use chrono::DateTime;
 use csv::Writer;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 const PB_ENDPOINT: &str = "https://api.pinboard.in/v1";
+const RD_ENDPOINT: &str = "https://api.raindrop.io/rest/v1";
+const RD_BATCH_SIZE: usize = 100;
+const RD_MAX_RETRIES: u32 = 5;
+const KEYRING_SERVICE: &str = "p2r";
+const KEYRING_USER: &str = "pinboard_token";
 
 #[derive(Deserialize, Debug, Clone)]
 struct PinboardBookmark {
@@ -18,6 +24,19 @@ struct PinboardBookmark {
     #[serde(rename(deserialize = "extended"))]
     description: String,
     tags: String,
+    #[serde(rename(deserialize = "shared"), deserialize_with = "de_yes_no")]
+    shared: bool,
+    #[serde(rename(deserialize = "toread"), deserialize_with = "de_yes_no")]
+    toread: bool,
+}
+
+// Pinboard encodes booleans as the strings "yes"/"no" rather than JSON booleans
+fn de_yes_no<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Ok(s == "yes")
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -30,11 +49,140 @@ struct RaindropBookmark {
     created: String, // ISO 8601 format
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Rss,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "rss" => Ok(OutputFormat::Rss),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(anyhow::anyhow!(
+                "unknown output format '{}': expected csv, rss, or json",
+                other
+            )),
+        }
+    }
+}
+
 struct TransformProps<'a> {
     pinboard_token: &'a str,
     raindrop_folder: &'a str,
     user_tags: &'a Option<String>,
     clean_description: &'a bool,
+    derive_titles: &'a bool,
+    folder_from_tag: FolderFromTag<'a>,
+    filter: &'a dyn Fn(&PinboardBookmark) -> bool,
+}
+
+struct FilterOpts {
+    include_tag: Vec<String>,
+    exclude_tag: Vec<String>,
+    domain: Vec<String>,
+    since: Option<chrono::DateTime<chrono::FixedOffset>>,
+    until: Option<chrono::DateTime<chrono::FixedOffset>>,
+    only_public: bool,
+    only_toread: bool,
+}
+
+// build a composable predicate applied to each fetched bookmark before export
+fn bookmark_filter(opts: FilterOpts) -> impl Fn(&PinboardBookmark) -> bool {
+    move |bm: &PinboardBookmark| {
+        if opts.only_public && !bm.shared {
+            return false;
+        }
+
+        if opts.only_toread && !bm.toread {
+            return false;
+        }
+
+        if !opts.include_tag.is_empty() {
+            let tags: Vec<&str> = bm.tags.split_whitespace().collect();
+            if !opts.include_tag.iter().any(|t| tags.contains(&t.as_str())) {
+                return false;
+            }
+        }
+
+        if !opts.exclude_tag.is_empty() {
+            let tags: Vec<&str> = bm.tags.split_whitespace().collect();
+            if opts.exclude_tag.iter().any(|t| tags.contains(&t.as_str())) {
+                return false;
+            }
+        }
+
+        if !opts.domain.is_empty() {
+            let host = reqwest::Url::parse(&bm.url)
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_string));
+            match host {
+                Some(h) => {
+                    if !opts.domain.iter().any(|d| d == &h) {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        if let Some(since) = &opts.since {
+            match DateTime::parse_from_rfc3339(&bm.created) {
+                Ok(created) if created >= *since => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(until) = &opts.until {
+            match DateTime::parse_from_rfc3339(&bm.created) {
+                Ok(created) if created <= *until => (),
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+struct FolderFromTag<'a> {
+    prefix: &'a Option<String>,
+    separator: &'a str,
+}
+
+// split off the first tag matching `prefix` (eg "folder/dev/rust") and turn the remainder
+// after the prefix into a nested folder path under `base_folder`, leaving the other tags intact
+fn split_hierarchical_tag(
+    tags: &str,
+    base_folder: &str,
+    folder_from_tag: &FolderFromTag,
+) -> (String, String) {
+    let prefix = match folder_from_tag.prefix {
+        Some(prefix) => prefix,
+        None => return (base_folder.to_string(), tags.to_string()),
+    };
+
+    let mut folder = base_folder.to_string();
+    let mut consumed = false;
+    let mut remaining = Vec::new();
+
+    for tag in tags.split_whitespace() {
+        if !consumed && tag.starts_with(prefix.as_str()) {
+            let rest = &tag[prefix.len()..];
+            if !rest.is_empty() {
+                folder = format!("{}{}{}", base_folder, folder_from_tag.separator, rest);
+                consumed = true;
+                continue;
+            }
+        }
+        remaining.push(tag);
+    }
+
+    (folder, remaining.join(" "))
 }
 
 impl PinboardBookmark {
@@ -58,6 +206,8 @@ impl PinboardBookmark {
         folder: &str,
         user_tags: &Option<String>,
         clean_description: &bool,
+        derive_titles: &bool,
+        folder_from_tag: &FolderFromTag,
     ) -> Result<RaindropBookmark> {
         // validate ISO dates
         let _datetime = DateTime::parse_from_rfc3339(&self.created)?;
@@ -65,20 +215,90 @@ impl PinboardBookmark {
             true => Self::clean_description(&self.description),
             false => self.description,
         };
+        let title = match *derive_titles && self.title.trim().is_empty() {
+            true => derive_title_from_url(&self.url),
+            false => self.title,
+        };
+        let (folder, tags) = split_hierarchical_tag(&self.tags, folder, folder_from_tag);
 
         let bm = RaindropBookmark {
             url: self.url,
-            folder: folder.to_string(),
-            title: self.title,
+            folder,
+            title,
             description,
-            tags: Self::tag(&self.tags, user_tags),
+            tags: Self::tag(&tags, user_tags),
             created: self.created,
         };
         Ok(bm)
     }
 }
 
-fn write_file(output: PathBuf, data: Vec<RaindropBookmark>) -> Result<()> {
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn title_case(s: &str) -> String {
+    s.split_whitespace()
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                None => String::new(),
+                Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn strip_extension(s: &str) -> &str {
+    match s.rfind('.') {
+        Some(idx) if idx > 0 => &s[..idx],
+        _ => s,
+    }
+}
+
+// build a human-readable title from a bookmark's URL when Pinboard has none
+fn derive_title_from_url(url: &str) -> String {
+    let (raw, is_path_segment) = match reqwest::Url::parse(url) {
+        Ok(parsed) => {
+            let last_segment = parsed
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()).map(str::to_string));
+            match last_segment {
+                Some(segment) => (segment, true),
+                None => (parsed.host_str().unwrap_or(url).to_string(), false),
+            }
+        }
+        Err(_) => (url.to_string(), false),
+    };
+
+    let decoded = percent_decode(&raw);
+    let spaced = decoded.replace(['-', '_', '+'], " ");
+    let trimmed = spaced.trim();
+    let name = if is_path_segment {
+        strip_extension(trimmed)
+    } else {
+        trimmed
+    };
+    title_case(name)
+}
+
+fn write_csv(output: PathBuf, data: Vec<RaindropBookmark>) -> Result<()> {
     let mut wtr = Writer::from_path(output)?;
     for datum in data {
         wtr.serialize(datum)?;
@@ -87,24 +307,313 @@ fn write_file(output: PathBuf, data: Vec<RaindropBookmark>) -> Result<()> {
     Ok(())
 }
 
-/// display the number of successfully processed bookmarks and errors
-fn stats(n_ok: usize, n_error: usize) {
+fn write_json(output: PathBuf, data: Vec<RaindropBookmark>) -> Result<()> {
+    let file = std::fs::File::create(output)?;
+    serde_json::to_writer_pretty(file, &data)?;
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn rss_item(bm: &RaindropBookmark) -> Result<String> {
+    let pub_date = DateTime::parse_from_rfc3339(&bm.created)?.to_rfc2822();
+    let description = match bm.tags.trim() {
+        "" => bm.description.clone(),
+        tags => format!("{} Tags: {}", bm.description, tags),
+    };
+
+    Ok(format!(
+        "    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <description>{}</description>\n      <category>{}</category>\n      <pubDate>{}</pubDate>\n    </item>\n",
+        xml_escape(&bm.title),
+        xml_escape(&bm.url),
+        xml_escape(&description),
+        xml_escape(&bm.folder),
+        pub_date
+    ))
+}
+
+fn write_rss(output: PathBuf, data: Vec<RaindropBookmark>) -> Result<()> {
+    let mut items = String::new();
+    for datum in &data {
+        items.push_str(&rss_item(datum)?);
+    }
+
+    let feed = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n    <title>Pinboard Imports</title>\n    <link>https://raindrop.io</link>\n    <description>Bookmarks exported from Pinboard to Raindrop.io</description>\n{}  </channel>\n</rss>\n",
+        items
+    );
+
+    std::fs::write(output, feed)?;
+    Ok(())
+}
+
+fn write_file(
+    output: PathBuf,
+    data: Vec<RaindropBookmark>,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Csv => write_csv(output, data),
+        OutputFormat::Rss => write_rss(output, data),
+        OutputFormat::Json => write_json(output, data),
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct RdCollection {
+    #[serde(rename(deserialize = "_id"))]
+    id: i64,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RdCollectionsResponse {
+    items: Vec<RdCollection>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RdCollectionResponse {
+    item: RdCollection,
+}
+
+#[derive(Serialize, Debug)]
+struct RdCollectionRef {
+    #[serde(rename(serialize = "$id"))]
+    id: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct RdRaindropItem {
+    link: String,
+    title: String,
+    excerpt: String,
+    tags: Vec<String>,
+    created: String,
+    collection: RdCollectionRef,
+}
+
+impl RdRaindropItem {
+    fn from_bookmark(bm: &RaindropBookmark, collection_id: i64) -> Self {
+        RdRaindropItem {
+            link: bm.url.clone(),
+            title: bm.title.clone(),
+            excerpt: bm.description.clone(),
+            tags: bm
+                .tags
+                .split_whitespace()
+                .map(|t| t.to_string())
+                .collect(),
+            created: bm.created.clone(),
+            collection: RdCollectionRef { id: collection_id },
+        }
+    }
+}
+
+// Raindrop's GET /collections only lists root-level collections; children of a
+// collection live at /collections/childrens/{parent_id}.
+async fn list_collections(
+    client: &reqwest::Client,
+    raindrop_token: &str,
+    parent_id: Option<i64>,
+) -> Result<Vec<RdCollection>> {
+    let url = match parent_id {
+        Some(id) => format!("{}/collections/childrens/{}", RD_ENDPOINT, id),
+        None => format!("{}/collections", RD_ENDPOINT),
+    };
+    let resp: RdCollectionsResponse = client
+        .get(url)
+        .bearer_auth(raindrop_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(resp.items)
+}
+
+// collections already resolved/created this run, keyed by (parent_id, title), so folder
+// paths that share a prefix (eg "Pinboard Imports/dev/rust" and "Pinboard Imports/dev/go")
+// don't repeat the same lookups and creations
+type CollectionCache = std::collections::HashMap<(Option<i64>, String), i64>;
+
+// find the collection titled `title` directly under `parent_id` (root if `None`),
+// creating it as a child of `parent_id` if it doesn't exist yet
+async fn resolve_collection_level(
+    client: &reqwest::Client,
+    raindrop_token: &str,
+    title: &str,
+    parent_id: Option<i64>,
+    cache: &mut CollectionCache,
+) -> Result<i64> {
+    let cache_key = (parent_id, title.to_string());
+    if let Some(id) = cache.get(&cache_key) {
+        return Ok(*id);
+    }
+
+    let existing = list_collections(client, raindrop_token, parent_id).await?;
+    if let Some(found) = existing.into_iter().find(|c| c.title == title) {
+        cache.insert(cache_key, found.id);
+        return Ok(found.id);
+    }
+
+    let mut body = serde_json::json!({ "title": title });
+    if let Some(id) = parent_id {
+        body["parent"] = serde_json::json!({ "$id": id });
+    }
+
+    let created: RdCollectionResponse = client
+        .post(format!("{}/collection", RD_ENDPOINT))
+        .bearer_auth(raindrop_token)
+        .json(&body)
+        .send()
+        .await?
+        .json()
+        .await?;
+    cache.insert(cache_key, created.item.id);
+    Ok(created.item.id)
+}
+
+// find the Raindrop collection addressed by `folder`, a path of `separator`-joined
+// segments (e.g. "Pinboard Imports/dev/rust"), creating each missing level as a real
+// nested collection rather than one literally titled with the separator in it
+async fn resolve_collection(
+    client: &reqwest::Client,
+    raindrop_token: &str,
+    folder: &str,
+    separator: &str,
+    cache: &mut CollectionCache,
+) -> Result<i64> {
+    let mut parent_id = None;
+    for segment in folder.split(separator).filter(|s| !s.is_empty()) {
+        parent_id = Some(
+            resolve_collection_level(client, raindrop_token, segment, parent_id, cache).await?,
+        );
+    }
+    parent_id.ok_or_else(|| anyhow::anyhow!("empty Raindrop folder path"))
+}
+
+// POST a single batch of raindrops, retrying with backoff on HTTP 429
+async fn post_batch(
+    client: &reqwest::Client,
+    raindrop_token: &str,
+    items: &[RdRaindropItem],
+) -> Result<()> {
+    let url = format!("{}/raindrops", RD_ENDPOINT);
+    for attempt in 0..=RD_MAX_RETRIES {
+        let resp = client
+            .post(&url)
+            .bearer_auth(raindrop_token)
+            .json(&serde_json::json!({ "items": items }))
+            .send()
+            .await?;
+
+        match resp.status().as_u16() {
+            200 | 201 => return Ok(()),
+            429 if attempt < RD_MAX_RETRIES => {
+                let backoff = Duration::from_millis(500 * 2u64.pow(attempt));
+                tokio::time::sleep(backoff).await;
+            }
+            s => return Err(anyhow::anyhow!("HTTP {}: Raindrop upload failed", s)),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "HTTP 429: Raindrop upload failed after {} retries",
+        RD_MAX_RETRIES
+    ))
+}
+
+// upload bookmarks directly to Raindrop, batching requests into groups of `RD_BATCH_SIZE`.
+// bookmarks are grouped by their (possibly tag-derived, possibly nested) `folder` so each
+// group lands in its own Raindrop collection instead of a single shared one.
+async fn upload(
+    raindrop_token: &str,
+    folder_separator: &str,
+    bms: Vec<RaindropBookmark>,
+) -> Result<Vec<Result<()>>> {
+    let client = reqwest::Client::new();
+
+    let mut groups: Vec<(String, Vec<RaindropBookmark>)> = Vec::new();
+    let mut group_index: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for bm in bms {
+        match group_index.get(&bm.folder) {
+            Some(&idx) => groups[idx].1.push(bm),
+            None => {
+                group_index.insert(bm.folder.clone(), groups.len());
+                groups.push((bm.folder.clone(), vec![bm]));
+            }
+        }
+    }
+
+    let mut cache = CollectionCache::new();
+    let mut results = Vec::new();
+    for (folder, group) in groups {
+        let collection_id =
+            match resolve_collection(&client, raindrop_token, &folder, folder_separator, &mut cache).await {
+                Ok(id) => id,
+                Err(e) => {
+                    let msg = e.to_string();
+                    results.extend(group.iter().map(move |_| Err(anyhow::anyhow!(msg.clone()))));
+                    continue;
+                }
+            };
+
+        for chunk in group.chunks(RD_BATCH_SIZE) {
+            let items: Vec<RdRaindropItem> = chunk
+                .iter()
+                .map(|bm| RdRaindropItem::from_bookmark(bm, collection_id))
+                .collect();
+
+            match post_batch(&client, raindrop_token, &items).await {
+                Ok(()) => results.extend(chunk.iter().map(|_| Ok(()))),
+                Err(e) => {
+                    let msg = e.to_string();
+                    results.extend(chunk.iter().map(move |_| Err(anyhow::anyhow!(msg.clone()))));
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// display the number of successfully processed bookmarks, errors, and filtered-out bookmarks
+fn stats(n_ok: usize, n_error: usize, n_filtered: usize) {
     let success_msg = format!("✓ {} bookmarks successfully processed", n_ok);
     let error_msg = format!("✕ {} bookmark processing errors ", n_error);
+    let filtered_msg = format!("↷ {} bookmarks filtered out", n_filtered);
     println!("{}", success_msg);
     println!("{}", error_msg);
+    println!("{}", filtered_msg);
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// store the Pinboard API token in the OS keyring instead of passing it on the command line
+    Login {
+        /// token to store: eg. "johndoe:xxx...". prompted for if omitted
+        #[structopt(short, long)]
+        pinboard_token: Option<String>,
+    },
 }
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "pinboard-to-raindrop")]
 struct Opt {
-    /// API token:eg. "johndoe:xxx...";
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// API token: eg. "johndoe:xxx...". falls back to the OS keyring, then the
+    /// PINBOARD_TOKEN env var, if omitted
     #[structopt(short, long)]
-    pinboard_token: String,
+    pinboard_token: Option<String>,
 
-    /// output file with raindrop formatted bookmarks
+    /// output file with raindrop formatted bookmarks. required unless --upload is set
     #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    output: Option<PathBuf>,
 
     /// target location in Raindrop for the uploaded bookmarks
     #[structopt(short, long, default_value = "Pinboard Imports")]
@@ -117,6 +626,104 @@ struct Opt {
     /// clean up descriptions by removing linebreaks
     #[structopt(short, long)]
     clean_description: bool,
+
+    /// output format: csv, rss, or json
+    #[structopt(short, long, default_value = "csv")]
+    format: OutputFormat,
+
+    /// upload bookmarks directly to Raindrop instead of writing a file
+    #[structopt(long)]
+    upload: bool,
+
+    /// Raindrop.io API token, required when --upload is set
+    #[structopt(long)]
+    raindrop_token: Option<String>,
+
+    /// only include bookmarks with this tag (may be repeated)
+    #[structopt(long)]
+    include_tag: Vec<String>,
+
+    /// exclude bookmarks with this tag (may be repeated)
+    #[structopt(long)]
+    exclude_tag: Vec<String>,
+
+    /// only include bookmarks whose URL host matches this domain (may be repeated)
+    #[structopt(long)]
+    domain: Vec<String>,
+
+    /// only include bookmarks created on or after this RFC3339 timestamp
+    #[structopt(long)]
+    since: Option<String>,
+
+    /// only include bookmarks created on or before this RFC3339 timestamp
+    #[structopt(long)]
+    until: Option<String>,
+
+    /// only include bookmarks that are public on Pinboard
+    #[structopt(long)]
+    only_public: bool,
+
+    /// only include bookmarks marked "read later" on Pinboard
+    #[structopt(long)]
+    only_toread: bool,
+
+    /// incrementally sync: skip the fetch entirely if Pinboard reports no changes, and only
+    /// write/upload bookmarks that are new or modified since the last run
+    #[structopt(long)]
+    sync: bool,
+
+    /// path to the local sync state cache (defaults to the --output path with its
+    /// extension replaced by .p2r-state.json, eg. bookmarks.p2r-state.json for bookmarks.csv)
+    #[structopt(long, parse(from_os_str))]
+    state_file: Option<PathBuf>,
+
+    /// derive a title from the URL when a bookmark's Pinboard description is empty
+    #[structopt(long)]
+    derive_titles: bool,
+
+    /// if a tag starts with this prefix (eg "folder/"), strip it and nest the remainder
+    /// under --raindrop-folder as a Raindrop folder path (eg "folder/dev/rust" becomes
+    /// "Pinboard Imports/dev/rust"), consuming at most one such tag per bookmark
+    #[structopt(long)]
+    folder_from_tag: Option<String>,
+
+    /// path separator used both in the matching tag and in the resulting folder path
+    #[structopt(long, default_value = "/")]
+    folder_separator: String,
+}
+
+// resolve the Pinboard token: CLI flag, then OS keyring, then PINBOARD_TOKEN env var
+fn resolve_pinboard_token(cli_token: Option<String>) -> Result<String> {
+    if let Some(token) = cli_token {
+        return Ok(token);
+    }
+
+    if let Ok(entry) = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER) {
+        if let Ok(token) = entry.get_password() {
+            return Ok(token);
+        }
+    }
+
+    if let Ok(token) = std::env::var("PINBOARD_TOKEN") {
+        return Ok(token);
+    }
+
+    Err(anyhow::anyhow!(
+        "no Pinboard API token found: pass --pinboard-token, run `p2r login`, or set PINBOARD_TOKEN"
+    ))
+}
+
+// prompt for a Pinboard token, if not given, and store it in the OS keyring
+fn login(pinboard_token: Option<String>) -> Result<()> {
+    let token = match pinboard_token {
+        Some(token) => token,
+        None => rpassword::prompt_password("Pinboard API token: ")?,
+    };
+
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?;
+    entry.set_password(token.trim())?;
+    println!("✓ Pinboard API token stored in the OS keyring");
+    Ok(())
 }
 
 async fn pb_fetch(url: String) -> Result<Vec<PinboardBookmark>> {
@@ -134,6 +741,86 @@ async fn pb_fetch(url: String) -> Result<Vec<PinboardBookmark>> {
     }
 }
 
+#[derive(Deserialize, Debug)]
+struct PbUpdateResponse {
+    update_time: String,
+}
+
+async fn pb_update_time(pinboard_token: &str) -> Result<String> {
+    let url = format!(
+        "{}/posts/update?auth_token={}&format=json",
+        PB_ENDPOINT, pinboard_token
+    );
+    let resp: PbUpdateResponse = reqwest::get(url).await?.json().await?;
+    Ok(resp.update_time)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncState {
+    last_update: String,
+    // normalized url -> the `created` timestamp it was last exported with
+    bookmarks: std::collections::HashMap<String, String>,
+}
+
+// dedup key: Pinboard/Raindrop URLs differ only by a trailing slash or case in practice
+fn normalize_url(url: &str) -> String {
+    url.trim_end_matches('/').to_lowercase()
+}
+
+fn state_file_path(output: &std::path::Path) -> PathBuf {
+    output.with_extension("p2r-state.json")
+}
+
+fn load_sync_state(path: &std::path::Path) -> SyncState {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_sync_state(path: &std::path::Path, state: &SyncState) -> Result<()> {
+    let json = serde_json::to_string_pretty(state)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+// split bookmarks into those new/modified since the last sync, updating `state` in place
+fn diff_against_state(
+    bms: Vec<RaindropBookmark>,
+    state: &mut SyncState,
+) -> (Vec<RaindropBookmark>, usize, usize, usize) {
+    let mut changed = Vec::new();
+    let mut added = 0;
+    let mut updated = 0;
+    let mut unchanged = 0;
+
+    for bm in bms {
+        let key = normalize_url(&bm.url);
+        match state.bookmarks.get(&key) {
+            None => {
+                added += 1;
+                state.bookmarks.insert(key, bm.created.clone());
+                changed.push(bm);
+            }
+            Some(prev_created) if prev_created != &bm.created => {
+                updated += 1;
+                state.bookmarks.insert(key, bm.created.clone());
+                changed.push(bm);
+            }
+            Some(_) => unchanged += 1,
+        }
+    }
+
+    (changed, added, updated, unchanged)
+}
+
+/// display the number of bookmarks added, updated, and left unchanged by an incremental sync
+fn sync_stats(added: usize, updated: usize, unchanged: usize) {
+    println!("✓ {} bookmarks added", added);
+    println!("✓ {} bookmarks updated", updated);
+    println!("↷ {} bookmarks unchanged", unchanged);
+}
+
 async fn raindrop(
     p: TransformProps<'_>,
 ) -> Result<Vec<Result<RaindropBookmark>>> {
@@ -142,39 +829,108 @@ async fn raindrop(
         raindrop_folder,
         user_tags,
         clean_description,
+        derive_titles,
+        folder_from_tag,
+        filter,
     } = p;
     let url = format!(
         "{}/posts/all?auth_token={}&format=json",
         PB_ENDPOINT, pinboard_token
     );
-    let (valid, errors): (Vec<Result<RaindropBookmark>>, Vec<_>) =
-        pb_fetch(url)
-            .await?
-            .into_iter()
-            .map(|bm| {
-                bm.into_raindrop(raindrop_folder, user_tags, clean_description)
-            })
-            .partition(Result::is_ok);
+    let fetched = pb_fetch(url).await?;
+    let n_fetched = fetched.len();
 
-    stats(valid.len(), errors.len());
+    let (valid, errors): (Vec<Result<RaindropBookmark>>, Vec<_>) = fetched
+        .into_iter()
+        .filter(filter)
+        .map(|bm| {
+            bm.into_raindrop(
+                raindrop_folder,
+                user_tags,
+                clean_description,
+                derive_titles,
+                &folder_from_tag,
+            )
+        })
+        .partition(Result::is_ok);
+
+    let n_filtered = n_fetched - valid.len() - errors.len();
+    stats(valid.len(), errors.len(), n_filtered);
     Ok(valid)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let Opt {
+        command,
         pinboard_token,
         output,
         raindrop_folder,
         user_tags,
         clean_description,
+        format,
+        upload: do_upload,
+        raindrop_token,
+        include_tag,
+        exclude_tag,
+        domain,
+        since,
+        until,
+        only_public,
+        only_toread,
+        sync,
+        state_file,
+        derive_titles,
+        folder_from_tag,
+        folder_separator,
     } = Opt::from_args();
 
+    if let Some(Command::Login { pinboard_token }) = command {
+        return login(pinboard_token);
+    }
+
+    let pinboard_token = resolve_pinboard_token(pinboard_token)?;
+
+    let state_path = state_file.or_else(|| output.as_ref().map(|o| state_file_path(o)));
+    let mut sync_state = SyncState::default();
+    let mut pb_update: Option<String> = None;
+    if sync {
+        let state_path = state_path.clone().ok_or_else(|| {
+            anyhow::anyhow!("--state-file is required for --sync when --output is not set")
+        })?;
+        sync_state = load_sync_state(&state_path);
+
+        let update_time = pb_update_time(&pinboard_token).await?;
+        if update_time == sync_state.last_update {
+            sync_stats(0, 0, sync_state.bookmarks.len());
+            return Ok(());
+        }
+        pb_update = Some(update_time);
+    }
+
+    let since = since.map(|s| DateTime::parse_from_rfc3339(&s)).transpose()?;
+    let until = until.map(|s| DateTime::parse_from_rfc3339(&s)).transpose()?;
+    let filter = bookmark_filter(FilterOpts {
+        include_tag,
+        exclude_tag,
+        domain,
+        since,
+        until,
+        only_public,
+        only_toread,
+    });
+
     let props = TransformProps {
         pinboard_token: &pinboard_token,
         raindrop_folder: &raindrop_folder,
         user_tags: &user_tags,
         clean_description: &clean_description,
+        derive_titles: &derive_titles,
+        folder_from_tag: FolderFromTag {
+            prefix: &folder_from_tag,
+            separator: &folder_separator,
+        },
+        filter: &filter,
     };
 
     let bms = raindrop(props)
@@ -183,7 +939,29 @@ async fn main() -> Result<()> {
         .map(|bm| bm.unwrap())
         .collect::<Vec<RaindropBookmark>>();
 
-    write_file(output, bms)?;
+    let bms = if sync {
+        let (changed, added, updated, unchanged) = diff_against_state(bms, &mut sync_state);
+        sync_stats(added, updated, unchanged);
+        sync_state.last_update = pb_update.unwrap_or_default();
+        save_sync_state(state_path.as_ref().unwrap(), &sync_state)?;
+        changed
+    } else {
+        bms
+    };
+
+    if do_upload {
+        let token = raindrop_token
+            .ok_or_else(|| anyhow::anyhow!("--raindrop-token is required when --upload is set"))?;
+        let (valid, errors): (Vec<Result<()>>, Vec<_>) = upload(&token, &folder_separator, bms)
+            .await?
+            .into_iter()
+            .partition(Result::is_ok);
+        stats(valid.len(), errors.len(), 0);
+    } else {
+        let output = output
+            .ok_or_else(|| anyhow::anyhow!("--output is required unless --upload is set"))?;
+        write_file(output, bms, format)?;
+    }
 
     Ok(())
 }
@@ -225,6 +1003,8 @@ mod tests {
             created: created.to_string(),
             description: description.to_string(),
             tags: tags.to_string(),
+            shared: true,
+            toread: false,
         };
 
         let rd_bm = RaindropBookmark {
@@ -236,7 +1016,16 @@ mod tests {
             created: created.to_string(),
         };
         let bm = pb_bm
-            .into_raindrop(folder, &Some(user_tags.to_string()), &true)
+            .into_raindrop(
+                folder,
+                &Some(user_tags.to_string()),
+                &true,
+                &false,
+                &FolderFromTag {
+                    prefix: &None,
+                    separator: "/",
+                },
+            )
             .unwrap();
         assert_eq!(rd_bm, bm)
     }
@@ -255,10 +1044,302 @@ mod tests {
             created: created.to_string(),
             description: description.to_string(),
             tags: tags.to_string(),
+            shared: true,
+            toread: false,
         };
 
-        let bm =
-            pb_bm.into_raindrop(folder, &Some(user_tags.to_string()), &true);
+        let bm = pb_bm.into_raindrop(
+            folder,
+            &Some(user_tags.to_string()),
+            &true,
+            &false,
+            &FolderFromTag {
+                prefix: &None,
+                separator: "/",
+            },
+        );
         assert_eq!(true, bm.is_err())
     }
+
+    #[test]
+    fn filter_only_public_excludes_private_bookmarks() {
+        let mut bm = PinboardBookmark {
+            url: "https://example.com".to_string(),
+            title: "title".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+            description: "".to_string(),
+            tags: "a b".to_string(),
+            shared: false,
+            toread: false,
+        };
+
+        let filter = bookmark_filter(FilterOpts {
+            include_tag: vec![],
+            exclude_tag: vec![],
+            domain: vec![],
+            since: None,
+            until: None,
+            only_public: true,
+            only_toread: false,
+        });
+
+        assert_eq!(false, filter(&bm));
+        bm.shared = true;
+        assert_eq!(true, filter(&bm));
+    }
+
+    #[test]
+    fn filter_only_toread_excludes_read_bookmarks() {
+        let mut bm = PinboardBookmark {
+            url: "https://example.com".to_string(),
+            title: "title".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+            description: "".to_string(),
+            tags: "".to_string(),
+            shared: true,
+            toread: false,
+        };
+
+        let filter = bookmark_filter(FilterOpts {
+            include_tag: vec![],
+            exclude_tag: vec![],
+            domain: vec![],
+            since: None,
+            until: None,
+            only_public: false,
+            only_toread: true,
+        });
+
+        assert_eq!(false, filter(&bm));
+        bm.toread = true;
+        assert_eq!(true, filter(&bm));
+    }
+
+    #[test]
+    fn filter_include_and_exclude_tags() {
+        let bm = PinboardBookmark {
+            url: "https://example.com".to_string(),
+            title: "title".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+            description: "".to_string(),
+            tags: "rust cli".to_string(),
+            shared: true,
+            toread: false,
+        };
+
+        let include = bookmark_filter(FilterOpts {
+            include_tag: vec!["go".to_string()],
+            exclude_tag: vec![],
+            domain: vec![],
+            since: None,
+            until: None,
+            only_public: false,
+            only_toread: false,
+        });
+        assert_eq!(false, include(&bm));
+
+        let exclude = bookmark_filter(FilterOpts {
+            include_tag: vec![],
+            exclude_tag: vec!["cli".to_string()],
+            domain: vec![],
+            since: None,
+            until: None,
+            only_public: false,
+            only_toread: false,
+        });
+        assert_eq!(false, exclude(&bm));
+    }
+
+    #[test]
+    fn diff_against_state_classifies_added_updated_unchanged() {
+        let mut state = SyncState::default();
+        state
+            .bookmarks
+            .insert(normalize_url("https://a.com/"), "2020-01-01T00:00:00Z".to_string());
+        state
+            .bookmarks
+            .insert(normalize_url("https://b.com/"), "2020-01-01T00:00:00Z".to_string());
+
+        let bms = vec![
+            RaindropBookmark {
+                url: "https://a.com/".to_string(),
+                folder: "Imported".to_string(),
+                title: "a".to_string(),
+                description: "".to_string(),
+                tags: "".to_string(),
+                created: "2020-01-01T00:00:00Z".to_string(),
+            },
+            RaindropBookmark {
+                url: "https://b.com/".to_string(),
+                folder: "Imported".to_string(),
+                title: "b".to_string(),
+                description: "".to_string(),
+                tags: "".to_string(),
+                created: "2021-06-01T00:00:00Z".to_string(),
+            },
+            RaindropBookmark {
+                url: "https://c.com/".to_string(),
+                folder: "Imported".to_string(),
+                title: "c".to_string(),
+                description: "".to_string(),
+                tags: "".to_string(),
+                created: "2022-01-01T00:00:00Z".to_string(),
+            },
+        ];
+
+        let (changed, added, updated, unchanged) = diff_against_state(bms, &mut state);
+        assert_eq!(1, added);
+        assert_eq!(1, updated);
+        assert_eq!(1, unchanged);
+        assert_eq!(2, changed.len());
+    }
+
+    #[test]
+    fn derive_title_from_url_uses_last_path_segment() {
+        let title = derive_title_from_url("https://example.com/blog/rust-async-await.html");
+        assert_eq!("Rust Async Await", title);
+    }
+
+    #[test]
+    fn derive_title_from_url_falls_back_to_host() {
+        let title = derive_title_from_url("https://example.com");
+        assert_eq!("Example.com", title);
+    }
+
+    #[test]
+    fn into_raindrop_derives_title_when_blank_and_gated_by_flag() {
+        let pb_bm = PinboardBookmark {
+            url: "https://example.com/my-cool_post+name".to_string(),
+            title: "  ".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+            description: "".to_string(),
+            tags: "".to_string(),
+            shared: true,
+            toread: false,
+        };
+
+        let no_folder_tag = FolderFromTag {
+            prefix: &None,
+            separator: "/",
+        };
+
+        let untouched = pb_bm
+            .clone()
+            .into_raindrop("Imported", &None, &false, &false, &no_folder_tag)
+            .unwrap();
+        assert_eq!("  ", untouched.title);
+
+        let derived = pb_bm
+            .into_raindrop("Imported", &None, &false, &true, &no_folder_tag)
+            .unwrap();
+        assert_eq!("My Cool Post Name", derived.title);
+    }
+
+    #[test]
+    fn split_hierarchical_tag_nests_folder_and_strips_only_first_match() {
+        let folder_from_tag = FolderFromTag {
+            prefix: &Some("folder/".to_string()),
+            separator: "/",
+        };
+
+        let (folder, tags) =
+            split_hierarchical_tag("folder/dev/rust folder/other rust", "Pinboard Imports", &folder_from_tag);
+
+        assert_eq!("Pinboard Imports/dev/rust", folder);
+        assert_eq!("folder/other rust", tags);
+    }
+
+    #[test]
+    fn split_hierarchical_tag_leaves_folder_unchanged_when_no_match() {
+        let folder_from_tag = FolderFromTag {
+            prefix: &Some("folder/".to_string()),
+            separator: "/",
+        };
+
+        let (folder, tags) =
+            split_hierarchical_tag("rust cli", "Pinboard Imports", &folder_from_tag);
+
+        assert_eq!("Pinboard Imports", folder);
+        assert_eq!("rust cli", tags);
+    }
+
+    #[test]
+    fn output_format_from_str_parses_known_formats() {
+        assert_eq!(OutputFormat::Csv, "csv".parse().unwrap());
+        assert_eq!(OutputFormat::Rss, "rss".parse().unwrap());
+        assert_eq!(OutputFormat::Json, "json".parse().unwrap());
+    }
+
+    #[test]
+    fn output_format_from_str_rejects_unknown_format() {
+        let result: Result<OutputFormat> = "yaml".parse();
+        assert_eq!(true, result.is_err());
+    }
+
+    #[test]
+    fn xml_escape_escapes_reserved_characters() {
+        let escaped = xml_escape("Tom & Jerry <says> \"hi\" 'bye'");
+        assert_eq!("Tom &amp; Jerry &lt;says&gt; &quot;hi&quot; &apos;bye&apos;", escaped);
+    }
+
+    #[test]
+    fn rss_item_includes_tags_in_description() {
+        let bm = RaindropBookmark {
+            url: "https://example.com".to_string(),
+            folder: "Imported".to_string(),
+            title: "Example <Title>".to_string(),
+            description: "a description".to_string(),
+            tags: "rust cli".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+        };
+
+        let item = rss_item(&bm).unwrap();
+        assert_eq!(true, item.contains("<title>Example &lt;Title&gt;</title>"));
+        assert_eq!(true, item.contains("<link>https://example.com</link>"));
+        assert_eq!(true, item.contains("a description Tags: rust cli"));
+        assert_eq!(true, item.contains("<category>Imported</category>"));
+    }
+
+    #[test]
+    fn rss_item_rejects_invalid_created_datetime() {
+        let bm = RaindropBookmark {
+            url: "https://example.com".to_string(),
+            folder: "Imported".to_string(),
+            title: "Example".to_string(),
+            description: "a description".to_string(),
+            tags: "".to_string(),
+            created: "not-a-date".to_string(),
+        };
+
+        assert_eq!(true, rss_item(&bm).is_err());
+    }
+
+    #[test]
+    fn write_json_round_trips_bookmarks() {
+        let bm = RaindropBookmark {
+            url: "https://example.com".to_string(),
+            folder: "Imported".to_string(),
+            title: "Example".to_string(),
+            description: "a description".to_string(),
+            tags: "rust cli".to_string(),
+            created: "2017-04-03T15:59:39Z".to_string(),
+        };
+
+        let output = std::env::temp_dir().join("p2r_write_json_round_trips_bookmarks.json");
+        write_json(output.clone(), vec![bm]).unwrap();
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        let parsed: Vec<RaindropBookmark> = serde_json::from_str(&written).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        assert_eq!(1, parsed.len());
+        assert_eq!("https://example.com", parsed[0].url);
+        assert_eq!("rust cli", parsed[0].tags);
+    }
+
+    #[test]
+    fn resolve_pinboard_token_prefers_cli_flag() {
+        let token = resolve_pinboard_token(Some("cli-token".to_string())).unwrap();
+        assert_eq!("cli-token", token);
+    }
 }